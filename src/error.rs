@@ -0,0 +1,48 @@
+use std::io;
+
+use thiserror::Error;
+
+/// Errors that can occur while decoding or encoding an FBX binary file.
+///
+/// Every variant that can be traced back to a position in the file carries the byte `offset` the
+/// reader (or writer) was at when the failure was detected, so malformed files can be diagnosed
+/// without resorting to ad-hoc `println!`s.
+#[derive(Error, Debug)]
+pub enum FbxError {
+    /// The file does not start with the `Kaydara FBX Binary` magic header.
+    #[error("bad FBX header magic: {0:?}")]
+    BadMagic([u8; 23]),
+    /// A property's type marker byte did not match any known [`Property`](../types/enum.Property.html) variant.
+    #[error("invalid property marker {marker:#x} at offset {offset:#x}")]
+    InvalidPropertyMarker {
+        offset: u64,
+        marker: u8
+    },
+    /// An array property declared an `encoding` value other than `0` (raw) or `1` (deflate).
+    #[error("unknown array encoding {encoding} at offset {offset:#x}")]
+    BadArrayEncoding {
+        offset: u64,
+        encoding: u32
+    },
+    /// A string property or node name was not valid UTF-8.
+    #[error("non UTF-8 string at offset {offset:#x}")]
+    NonUtf8String {
+        offset: u64
+    },
+    /// A deflate-encoded array failed to inflate while decoding.
+    #[error("failed to inflate array at offset {offset:#x}")]
+    Inflate {
+        offset: u64
+    },
+    /// An array failed to deflate while encoding.
+    #[error("failed to deflate array at offset {offset:#x}")]
+    Deflate {
+        offset: u64
+    },
+    /// Any other I/O failure while reading or writing the underlying stream.
+    #[error(transparent)]
+    Io(#[from] io::Error)
+}
+
+/// Convenience alias for `Result<T, FbxError>`.
+pub type Result<T> = ::std::result::Result<T, FbxError>;
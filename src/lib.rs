@@ -3,28 +3,44 @@
 
 extern crate bytepack;
 extern crate flate2;
+extern crate thiserror;
 
-use std::io::{Read, Result, Error, ErrorKind, Seek};
+use std::io::{Read, Seek, Write};
 use std::u64;
 
-use bytepack::LEUnpacker;
+use bytepack::{LEPacker, LEUnpacker};
 
+pub mod error;
+pub mod scene;
 pub mod types;
 
-use types::{Node, decode_node_list};
+use error::{FbxError, Result};
+use types::{FBX_MAGIC, Node, RecordLayout, StringPolicy, decode_node_list, encode_node_list};
 
-/// Decode a FBX file to a [`Node`](types/struct.Node.html) hierarchy.
-pub fn decode_fbx<R: Read + Seek>(r: &mut R) -> Result<Vec<Node>> {
+/// Decode a FBX file to a [`Node`](types/struct.Node.html) hierarchy, applying `policy` to
+/// node names and string properties that are not valid UTF-8.
+///
+/// For large files, consider [`types::FbxReader`](types/struct.FbxReader.html), which yields
+/// top-level nodes one at a time instead of decoding the whole tree up front.
+pub fn decode_fbx<R: Read + Seek>(r: &mut R, policy: StringPolicy) -> Result<Vec<Node>> {
     let mut header = [0u8; 23];
     r.read_exact(&mut header[..])?;
-    if &header != b"Kaydara FBX Binary  \x00\x1a\x00" {
-        return Err(Error::new(ErrorKind::InvalidData, "Invalid FBX header magic"));
+    if header != FBX_MAGIC {
+        return Err(FbxError::BadMagic(header));
     }
     let version = r.unpack::<u32>()?;
-    if version >= 7500 {
-        return Err(Error::new(ErrorKind::InvalidData, "Unsuported FBX version"));
-    }
-    return decode_node_list(r, u64::MAX);
+    let layout = RecordLayout::for_version(version);
+    return decode_node_list(r, u64::MAX, layout, policy);
+}
+
+/// FBX version written by [`encode_fbx`](fn.encode_fbx.html).
+const ENCODE_VERSION: u32 = 7400;
+
+/// Encode a [`Node`](types/struct.Node.html) hierarchy to a FBX binary file.
+pub fn encode_fbx<W: Write + Seek>(w: &mut W, nodes: &[Node]) -> Result<()> {
+    w.write_all(&FBX_MAGIC)?;
+    w.pack(ENCODE_VERSION)?;
+    return encode_node_list(w, nodes, RecordLayout::for_version(ENCODE_VERSION));
 }
 
 #[cfg(test)]
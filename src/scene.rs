@@ -0,0 +1,297 @@
+//! A "cooked" scene-graph layer built on top of the raw [`Node`](../types/struct.Node.html) tree
+//! decoded by [`decode_fbx`](../fn.decode_fbx.html). This resolves the `Objects` and
+//! `Connections` records into typed, ID-keyed structures instead of requiring callers to
+//! hand-walk node names and properties.
+
+use std::collections::HashMap;
+
+use types::{Node, Property};
+
+/// Geometry data for a `Geometry` object.
+#[derive(Clone, Debug, Default)]
+pub struct Geometry {
+    /// Vertex positions, decoded from the `Vertices` double array.
+    pub vertices: Vec<[f64; 3]>,
+    /// Flattened polygon vertex indices, decoded from `PolygonVertexIndex`. FBX stores the last
+    /// index of each polygon bitwise complemented (`!index`), which callers need to undo to split
+    /// this back into individual polygons.
+    pub polygon_vertex_index: Vec<i32>,
+    /// Per-vertex normals, decoded from the `LayerElementNormal` sub-node, if present.
+    pub normals: Vec<[f64; 3]>,
+    /// Per-vertex UV coordinates, decoded from the `LayerElementUV` sub-node, if present.
+    pub uvs: Vec<[f64; 2]>,
+}
+
+/// A `Model` object: a node of the scene hierarchy that instantiates geometry and materials.
+#[derive(Clone, Debug, Default)]
+pub struct Model {
+    /// FBX model subtype (e.g. `"Mesh"`, `"Null"`, `"Camera"`).
+    pub kind: String,
+}
+
+/// A `Material` object.
+#[derive(Clone, Debug, Default)]
+pub struct Material {
+    /// FBX material subtype (e.g. `"Phong"`, `"Lambert"`).
+    pub kind: String,
+}
+
+/// A FBX object, keyed by its object ID in [`Scene::objects`](struct.Scene.html#structfield.objects).
+#[derive(Clone, Debug)]
+pub enum Object {
+    Geometry(Geometry),
+    Model(Model),
+    Material(Material),
+    /// Any object class this crate does not interpret yet, kept by name so it isn't silently
+    /// dropped.
+    Other {
+        class: String,
+        kind: String
+    },
+}
+
+/// A cooked scene graph, resolved from the raw `Objects` and `Connections` top-level nodes of a
+/// decoded FBX file.
+#[derive(Clone, Debug, Default)]
+pub struct Scene {
+    /// Objects, keyed by their FBX object ID.
+    pub objects: HashMap<i64, Object>,
+    /// `(child, parent)` object ID pairs, resolved from the `Connections` node's `OO` records.
+    pub connections: Vec<(i64, i64)>,
+}
+
+impl Scene {
+    /// Interpret the `Objects` and `Connections` top-level nodes of a decoded FBX file into a
+    /// [`Scene`](struct.Scene.html). Top-level nodes this crate does not interpret (e.g.
+    /// `Definitions`, `Takes`) are ignored.
+    pub fn from_nodes(nodes: &[Node]) -> Scene {
+        let mut scene = Scene::default();
+        for node in nodes {
+            match node.name.as_str() {
+                "Objects" => scene.load_objects(node),
+                "Connections" => scene.load_connections(node),
+                _ => {}
+            }
+        }
+        return scene;
+    }
+
+    fn load_objects(&mut self, objects_node: &Node) {
+        for object_node in &objects_node.subnodes {
+            if let Some((id, object)) = decode_object(object_node) {
+                self.objects.insert(id, object);
+            }
+        }
+    }
+
+    fn load_connections(&mut self, connections_node: &Node) {
+        for connection_node in &connections_node.subnodes {
+            if connection_node.name != "C" {
+                continue;
+            }
+            let kind = property_string(connection_node.properties.get(0));
+            let child = property_i64(connection_node.properties.get(1));
+            let parent = property_i64(connection_node.properties.get(2));
+            if let (Some(kind), Some(child), Some(parent)) = (kind, child, parent) {
+                if kind == "OO" {
+                    self.connections.push((child, parent));
+                }
+            }
+        }
+    }
+}
+
+/// Decode an object node from the `Objects` list: `Geometry`, `Model`, `Material` or `Other`,
+/// keyed by its FBX object ID (the node's first property).
+fn decode_object(node: &Node) -> Option<(i64, Object)> {
+    let id = property_i64(node.properties.get(0))?;
+    let kind = property_string(node.properties.get(2)).unwrap_or_default();
+
+    let object = match node.name.as_str() {
+        "Geometry" => Object::Geometry(decode_geometry(node)),
+        "Model" => Object::Model(Model { kind: kind }),
+        "Material" => Object::Material(Material { kind: kind }),
+        class => Object::Other { class: class.to_string(), kind: kind }
+    };
+    return Some((id, object));
+}
+
+fn decode_geometry(node: &Node) -> Geometry {
+    let mut geometry = Geometry::default();
+    for sub in &node.subnodes {
+        match sub.name.as_str() {
+            "Vertices" => geometry.vertices = chunk3(&decode_f64_array(sub)),
+            "PolygonVertexIndex" => geometry.polygon_vertex_index = decode_i32_array(sub),
+            "LayerElementNormal" => geometry.normals = decode_vec3_layer(sub, "Normals"),
+            "LayerElementUV" => geometry.uvs = decode_vec2_layer(sub, "UV"),
+            _ => {}
+        }
+    }
+    return geometry;
+}
+
+fn decode_vec3_layer(node: &Node, child_name: &str) -> Vec<[f64; 3]> {
+    return node.subnodes.iter()
+        .find(|n| n.name == child_name)
+        .map(|n| chunk3(&decode_f64_array(n)))
+        .unwrap_or_default();
+}
+
+fn decode_vec2_layer(node: &Node, child_name: &str) -> Vec<[f64; 2]> {
+    return node.subnodes.iter()
+        .find(|n| n.name == child_name)
+        .map(|n| chunk2(&decode_f64_array(n)))
+        .unwrap_or_default();
+}
+
+fn chunk3(values: &[f64]) -> Vec<[f64; 3]> {
+    return values.chunks(3).filter(|c| c.len() == 3).map(|c| [c[0], c[1], c[2]]).collect();
+}
+
+fn chunk2(values: &[f64]) -> Vec<[f64; 2]> {
+    return values.chunks(2).filter(|c| c.len() == 2).map(|c| [c[0], c[1]]).collect();
+}
+
+fn decode_f64_array(node: &Node) -> Vec<f64> {
+    match node.properties.get(0) {
+        Some(&Property::F64Array(ref v)) => v.clone(),
+        _ => Vec::new()
+    }
+}
+
+fn decode_i32_array(node: &Node) -> Vec<i32> {
+    match node.properties.get(0) {
+        Some(&Property::I32Array(ref v)) => v.clone(),
+        _ => Vec::new()
+    }
+}
+
+fn property_i64(p: Option<&Property>) -> Option<i64> {
+    match p {
+        Some(&Property::I64(v)) => Some(v),
+        _ => None
+    }
+}
+
+fn property_string(p: Option<&Property>) -> Option<String> {
+    match p {
+        Some(&Property::String(ref s)) => Some(s.clone()),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model_node(id: i64, name: &str) -> Node {
+        Node {
+            name: "Model".to_string(),
+            properties: vec![
+                Property::I64(id),
+                Property::String(name.to_string()),
+                Property::String("Mesh".to_string())
+            ],
+            subnodes: vec![]
+        }
+    }
+
+    fn connection_node(child: i64, parent: i64) -> Node {
+        Node {
+            name: "C".to_string(),
+            properties: vec![Property::String("OO".to_string()), Property::I64(child), Property::I64(parent)],
+            subnodes: vec![]
+        }
+    }
+
+    #[test]
+    fn objects_and_connections() {
+        let nodes = vec![
+            Node {
+                name: "Objects".to_string(),
+                properties: vec![],
+                subnodes: vec![model_node(1, "Parent"), model_node(2, "Child")]
+            },
+            Node {
+                name: "Connections".to_string(),
+                properties: vec![],
+                subnodes: vec![connection_node(2, 1)]
+            }
+        ];
+
+        let scene = Scene::from_nodes(&nodes);
+
+        assert_eq!(scene.objects.len(), 2);
+        match scene.objects.get(&1) {
+            Some(&Object::Model(ref model)) => assert_eq!(model.kind, "Mesh"),
+            other => panic!("expected Model object, got {:?}", other)
+        }
+        assert_eq!(scene.connections, vec![(2, 1)]);
+    }
+
+    #[test]
+    fn geometry() {
+        let geometry_node = Node {
+            name: "Geometry".to_string(),
+            properties: vec![
+                Property::I64(10),
+                Property::String("cube".to_string()),
+                Property::String("Mesh".to_string())
+            ],
+            subnodes: vec![
+                Node {
+                    name: "Vertices".to_string(),
+                    properties: vec![Property::F64Array(vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0])],
+                    subnodes: vec![]
+                },
+                Node {
+                    name: "PolygonVertexIndex".to_string(),
+                    properties: vec![Property::I32Array(vec![0, 1, -2])],
+                    subnodes: vec![]
+                },
+                Node {
+                    name: "LayerElementNormal".to_string(),
+                    properties: vec![],
+                    subnodes: vec![
+                        Node {
+                            name: "Normals".to_string(),
+                            properties: vec![Property::F64Array(vec![0.0, 1.0, 0.0])],
+                            subnodes: vec![]
+                        }
+                    ]
+                },
+                Node {
+                    name: "LayerElementUV".to_string(),
+                    properties: vec![],
+                    subnodes: vec![
+                        Node {
+                            name: "UV".to_string(),
+                            properties: vec![Property::F64Array(vec![0.0, 0.0, 1.0, 1.0])],
+                            subnodes: vec![]
+                        }
+                    ]
+                }
+            ]
+        };
+
+        let nodes = vec![
+            Node {
+                name: "Objects".to_string(),
+                properties: vec![],
+                subnodes: vec![geometry_node]
+            }
+        ];
+
+        let scene = Scene::from_nodes(&nodes);
+
+        match scene.objects.get(&10) {
+            Some(&Object::Geometry(ref geometry)) => {
+                assert_eq!(geometry.vertices, vec![[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]]);
+                assert_eq!(geometry.polygon_vertex_index, vec![0, 1, -2]);
+                assert_eq!(geometry.normals, vec![[0.0, 1.0, 0.0]]);
+                assert_eq!(geometry.uvs, vec![[0.0, 0.0], [1.0, 1.0]]);
+            }
+            other => panic!("expected Geometry object, got {:?}", other)
+        }
+    }
+}
@@ -1,18 +1,179 @@
 use std::fs::File;
+use std::io::Cursor;
 
 use decode_fbx;
+use encode_fbx;
+use types::{FbxReader, Node, Property, RecordLayout, StringPolicy, decode_node_list, decode_property, encode_node_list, split_composite_name};
 
 #[test]
 fn blender_cube() {
     let mut cube_file = File::open("testcases/cube.fbx").unwrap();
-    let cube = decode_fbx(&mut cube_file).unwrap();
+    let cube = decode_fbx(&mut cube_file, StringPolicy::default()).unwrap();
     println!("{:?}", cube);
 }
 
 #[test]
 fn blender_multiples() {
     let mut multiples_file = File::open("testcases/multiples.fbx").unwrap();
-    let multiples = decode_fbx(&mut multiples_file).unwrap();
+    let multiples = decode_fbx(&mut multiples_file, StringPolicy::default()).unwrap();
     println!("{:?}", multiples);
 }
 
+#[test]
+fn encode_decode_round_trip() {
+    let nodes = vec![
+        Node {
+            name: "GlobalSettings".to_string(),
+            properties: vec![Property::I32(1000), Property::String("meters".to_string())],
+            subnodes: vec![
+                Node {
+                    name: "Version".to_string(),
+                    properties: vec![Property::I32(7)],
+                    subnodes: vec![]
+                }
+            ]
+        },
+        Node {
+            name: "Objects".to_string(),
+            properties: vec![],
+            subnodes: vec![
+                Node {
+                    name: "Geometry".to_string(),
+                    properties: vec![
+                        Property::I64(12345),
+                        Property::String("cube".to_string()),
+                        Property::String("Mesh".to_string())
+                    ],
+                    subnodes: vec![
+                        Node {
+                            name: "Vertices".to_string(),
+                            properties: vec![Property::F64Array(vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0])],
+                            subnodes: vec![]
+                        },
+                        Node {
+                            name: "PolygonVertexIndex".to_string(),
+                            properties: vec![Property::I32Array(vec![0, 1, -3])],
+                            subnodes: vec![]
+                        }
+                    ]
+                }
+            ]
+        }
+    ];
+
+    let mut buffer = Cursor::new(Vec::<u8>::new());
+    encode_fbx(&mut buffer, &nodes).unwrap();
+
+    buffer.set_position(0);
+    let decoded = decode_fbx(&mut buffer, StringPolicy::default()).unwrap();
+
+    assert_eq!(decoded, nodes);
+}
+
+#[test]
+fn encode_decode_round_trip_wide64() {
+    // `RecordLayout::Wide64` is only selected for FBX version 7500 and later; exercise its
+    // `u64` header fields directly rather than round-tripping through `encode_fbx`/`decode_fbx`:
+    // `encode_fbx` always writes `ENCODE_VERSION` (7400, i.e. `Wide32`).
+    let nodes = vec![
+        Node {
+            name: "GlobalSettings".to_string(),
+            properties: vec![Property::I32(1000)],
+            subnodes: vec![
+                Node { name: "Version".to_string(), properties: vec![Property::I32(7)], subnodes: vec![] }
+            ]
+        }
+    ];
+
+    let mut buffer = Cursor::new(Vec::<u8>::new());
+    encode_node_list(&mut buffer, &nodes, RecordLayout::Wide64).unwrap();
+
+    buffer.set_position(0);
+    let end = buffer.get_ref().len() as u64;
+    let decoded = decode_node_list(&mut buffer, end, RecordLayout::Wide64, StringPolicy::default()).unwrap();
+
+    assert_eq!(decoded, nodes);
+}
+
+fn three_node_file() -> Vec<u8> {
+    let nodes = vec![
+        Node {
+            name: "First".to_string(),
+            properties: vec![Property::I32(1)],
+            subnodes: vec![
+                Node { name: "Child".to_string(), properties: vec![], subnodes: vec![] }
+            ]
+        },
+        Node { name: "Second".to_string(), properties: vec![Property::I32(2)], subnodes: vec![] },
+        Node { name: "Third".to_string(), properties: vec![Property::I32(3)], subnodes: vec![] }
+    ];
+    let mut buffer = Cursor::new(Vec::<u8>::new());
+    encode_fbx(&mut buffer, &nodes).unwrap();
+    return buffer.into_inner();
+}
+
+#[test]
+fn fbx_reader_iterates_top_level_nodes() {
+    let buffer = three_node_file();
+    let reader = FbxReader::new(Cursor::new(buffer), StringPolicy::default()).unwrap();
+    let names : Vec<String> = reader.map(|n| n.unwrap().name).collect();
+    assert_eq!(names, vec!["First".to_string(), "Second".to_string(), "Third".to_string()]);
+}
+
+#[test]
+fn fbx_reader_skip_node_lands_on_next_sibling() {
+    let buffer = three_node_file();
+    let mut reader = FbxReader::new(Cursor::new(buffer), StringPolicy::default()).unwrap();
+
+    let first_header = reader.next_header().unwrap().unwrap();
+    assert_eq!(first_header.name, "First");
+    reader.skip_node(&first_header).unwrap();
+
+    let second = reader.next_node().unwrap().unwrap();
+    assert_eq!(second.name, "Second");
+
+    let third_header = reader.next_header().unwrap().unwrap();
+    assert_eq!(third_header.name, "Third");
+}
+
+#[test]
+fn decode_property_latin1_fallback() {
+    // 'S' marker, a little-endian u32 length prefix of 1, then a single 0xE9 byte: a lead byte with no
+    // continuation, so invalid UTF-8 on its own but a valid Latin-1 "\u{e9}" ('é').
+    let bytes = vec![b'S', 0x01, 0x00, 0x00, 0x00, 0xE9];
+    let mut cursor = Cursor::new(bytes.clone());
+    let property = decode_property(&mut cursor, StringPolicy::Latin1Fallback).unwrap();
+    assert_eq!(property, Property::String("\u{e9}".to_string()));
+
+    let mut cursor = Cursor::new(bytes);
+    assert!(decode_property(&mut cursor, StringPolicy::Strict).is_err());
+}
+
+#[test]
+fn split_composite_name_splits_on_separator() {
+    assert_eq!(split_composite_name("Name\x00\x01Property"), vec!["Name", "Property"]);
+    assert_eq!(split_composite_name("NoSeparator"), vec!["NoSeparator"]);
+}
+
+#[test]
+fn encode_decode_round_trip_small_array() {
+    // A 3-element i32 array: deflate framing overhead exceeds the input size for an array this
+    // small, which previously caused `encode_array`'s under-sized output buffer to silently
+    // truncate the compressed stream.
+    let nodes = vec![
+        Node {
+            name: "Tiny".to_string(),
+            properties: vec![Property::I32Array(vec![1, 2, 3])],
+            subnodes: vec![]
+        }
+    ];
+
+    let mut buffer = Cursor::new(Vec::<u8>::new());
+    encode_fbx(&mut buffer, &nodes).unwrap();
+
+    buffer.set_position(0);
+    let decoded = decode_fbx(&mut buffer, StringPolicy::default()).unwrap();
+
+    assert_eq!(decoded, nodes);
+}
+
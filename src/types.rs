@@ -1,13 +1,16 @@
-use std::io::{Read, Result, Error, ErrorKind, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::iter::repeat;
-use std::mem::{forget, size_of, zeroed};
+use std::mem::{size_of, zeroed};
 
-use bytepack::{LEUnpacker, Packed};
+use bytepack::{LEPacker, LEUnpacker, Packed};
 
-use flate2::{Decompress, Flush};
+use flate2::{Compression, Decompress, Flush};
+use flate2::write::ZlibEncoder;
+
+use error::{FbxError, Result};
 
 /// Represent a typed property of the FBX file format.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Property {
     B(bool),
     I16(i16),
@@ -25,9 +28,9 @@ pub enum Property {
     F64Array(Vec<f64>),
 }
 
-/// Represent a Node of the FBX file format. Each node has a `name` (or id) and is composed of a 
+/// Represent a Node of the FBX file format. Each node has a `name` (or id) and is composed of a
 /// list of [`Property`](enum.Property.html) and a list of sub [`Node`](struct.Node.html).
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Node {
     /// Name of the node.
     pub name: String,
@@ -37,6 +40,64 @@ pub struct Node {
     pub subnodes: Vec<Node>,
 }
 
+/// Width of the `end_offset`, `property_number` and `properties_size` fields of a node record.
+/// FBX versions before 7500 pack those three fields as `u32`, while 7500 and later use `u64`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordLayout {
+    /// Node records with 32-bit `end_offset`, `property_number` and `properties_size` fields.
+    Wide32,
+    /// Node records with 64-bit `end_offset`, `property_number` and `properties_size` fields,
+    /// used starting with FBX version 7500.
+    Wide64,
+}
+
+impl RecordLayout {
+    /// Determine the [`RecordLayout`](enum.RecordLayout.html) used by a given FBX `version`.
+    pub fn for_version(version: u32) -> RecordLayout {
+        if version >= 7500 {
+            RecordLayout::Wide64
+        }
+        else {
+            RecordLayout::Wide32
+        }
+    }
+}
+
+/// Decode the `end_offset`, `property_number` and `properties_size` header fields of a node
+/// record, using the field width given by `layout`.
+fn decode_node_header<R: Read>(r: &mut R, layout: RecordLayout) -> Result<(u64, usize, usize)> {
+    match layout {
+        RecordLayout::Wide32 => Ok((
+            r.unpack::<u32>()? as u64,
+            r.unpack::<u32>()? as usize,
+            r.unpack::<u32>()? as usize
+        )),
+        RecordLayout::Wide64 => Ok((
+            r.unpack::<u64>()?,
+            r.unpack::<u64>()? as usize,
+            r.unpack::<u64>()? as usize
+        ))
+    }
+}
+
+/// Encode the `end_offset`, `property_number` and `properties_size` header fields of a node
+/// record, using the field width given by `layout`.
+fn encode_node_header<W: Write>(w: &mut W, layout: RecordLayout, end_offset: u64, property_number: usize, properties_size: usize) -> Result<()> {
+    match layout {
+        RecordLayout::Wide32 => {
+            w.pack(end_offset as u32)?;
+            w.pack(property_number as u32)?;
+            w.pack(properties_size as u32)?;
+        }
+        RecordLayout::Wide64 => {
+            w.pack(end_offset)?;
+            w.pack(property_number as u64)?;
+            w.pack(properties_size as u64)?;
+        }
+    }
+    return Ok(());
+}
+
 /// Decode a `Property::RawArray`.
 fn decode_raw_array<R: Read>(r : &mut R) -> Result<Vec<u8>> {
     let length = r.unpack::<u32>()? as usize;
@@ -45,20 +106,58 @@ fn decode_raw_array<R: Read>(r : &mut R) -> Result<Vec<u8>> {
     return Ok(array);
 }
 
-/// Decode a `Property::String`.
-fn decode_string<R: Read>(r : &mut R) -> Result<String> {
-    match String::from_utf8(decode_raw_array(r)?) {
+/// Policy used to decode string properties and node names that are not valid UTF-8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StringPolicy {
+    /// Fail with [`FbxError::NonUtf8String`](../error/enum.FbxError.html#variant.NonUtf8String).
+    Strict,
+    /// Fall back to decoding the bytes as Latin-1, which always succeeds since every byte maps
+    /// to the Unicode code point of the same value. Some exporters write object names in Latin-1
+    /// rather than UTF-8.
+    Latin1Fallback,
+}
+
+impl Default for StringPolicy {
+    fn default() -> StringPolicy {
+        StringPolicy::Strict
+    }
+}
+
+/// Decode `bytes` as Latin-1: every byte maps directly to the Unicode code point of the same
+/// value, so this never fails.
+fn decode_latin1(bytes: &[u8]) -> String {
+    return bytes.iter().map(|&b| b as char).collect();
+}
+
+/// Split a `\x00\x01`-delimited composite name into its parts. FBX embeds this separator in
+/// `Connections` property strings that combine an object name and a property name.
+pub fn split_composite_name(name: &str) -> Vec<&str> {
+    return name.split("\x00\x01").collect();
+}
+
+/// Decode a `Property::String`, applying `policy` when the bytes are not valid UTF-8.
+fn decode_string<R: Read + Seek>(r : &mut R, policy: StringPolicy) -> Result<String> {
+    let offset = r.seek(SeekFrom::Current(0))?;
+    let bytes = decode_raw_array(r)?;
+    match String::from_utf8(bytes) {
         Ok(s) => Ok(s),
-        Err(_) => Err(Error::new(ErrorKind::InvalidData, "Invalid UTF-8 characters in string"))
+        Err(err) => match policy {
+            StringPolicy::Strict => Err(FbxError::NonUtf8String { offset: offset }),
+            StringPolicy::Latin1Fallback => Ok(decode_latin1(&err.into_bytes()))
+        }
     }
 }
 
 /// Decode a `Property::*Array`.
-fn decode_array<R: Read, T: Packed + Clone>(r : &mut R) -> Result<Vec<T>> {
+fn decode_array<R: Read + Seek, T: Packed + Clone>(r : &mut R) -> Result<Vec<T>> {
+    let offset = r.seek(SeekFrom::Current(0))?;
     let length = r.unpack::<u32>()? as usize;
     let encoding = r.unpack::<u32>()?;
     let compressed_length = r.unpack::<u32>()? as usize;
     if encoding == 0 {
+        // `zeroed()` here (and in the encoding == 1 branch below) only pre-sizes the buffer
+        // before `unpack_exact` overwrites every element; it is not the alignment/endianness
+        // unsound transmute that used to live in the encoding == 1 branch.
         let zero : T = unsafe { zeroed() };
         let mut array : Vec<T> = repeat(zero).take(length).collect();
         r.unpack_exact(&mut array[..])?;
@@ -71,26 +170,28 @@ fn decode_array<R: Read, T: Packed + Clone>(r : &mut R) -> Result<Vec<T>> {
 
         r.read_exact(&mut compressed)?;
         if let Err(_) = deflater.decompress(&compressed, &mut decompressed, Flush::Finish) {
-            return Err(Error::new(ErrorKind::InvalidData, "Failed to deflate array"));
+            return Err(FbxError::Inflate { offset: offset });
         }
 
-        // Safe because we made sure the length and capacity of decompressed is length * size_of::<T>() 
-        // and we properly forget decompressed
-        decompressed.shrink_to_fit();
-        unsafe {
-            let converted = Vec::<T>::from_raw_parts(decompressed.as_mut_ptr() as *mut T, length, length);
-            forget(decompressed);
-            return Ok(converted);
-        }
+        // Unpack through the existing LEUnpacker machinery instead of reinterpreting the raw
+        // bytes: decompressed is only u8-aligned, and a byte-for-byte cast would also get the
+        // element values wrong on big-endian hosts.
+        let zero : T = unsafe { zeroed() };
+        let mut array : Vec<T> = repeat(zero).take(length).collect();
+        Cursor::new(decompressed).unpack_exact(&mut array[..])?;
+        return Ok(array);
     }
     else {
-        return Err(Error::new(ErrorKind::InvalidData, "Unknown array encoding"));
+        return Err(FbxError::BadArrayEncoding { offset: offset, encoding: encoding });
     }
 }
 
-/// Decode a [`Property`](enum.Property.html).
-pub fn decode_property<R: Read>(r: &mut R) -> Result<Property> {
-    match r.unpack()? {
+/// Decode a [`Property`](enum.Property.html), applying `policy` to string properties that are
+/// not valid UTF-8.
+pub fn decode_property<R: Read + Seek>(r: &mut R, policy: StringPolicy) -> Result<Property> {
+    let offset = r.seek(SeekFrom::Current(0))?;
+    let marker = r.unpack::<u8>()?;
+    match marker {
         b'C' => Ok(Property::B(r.unpack::<u8>()? == 1)),
         b'Y' => Ok(Property::I16(r.unpack()?)),
         b'I' => Ok(Property::I32(r.unpack()?)),
@@ -98,22 +199,88 @@ pub fn decode_property<R: Read>(r: &mut R) -> Result<Property> {
         b'F' => Ok(Property::F32(r.unpack()?)),
         b'D' => Ok(Property::F64(r.unpack()?)),
         b'R' => Ok(Property::RawArray(decode_raw_array(r)?)),
-        b'S' => Ok(Property::String(decode_string(r)?)),
+        b'S' => Ok(Property::String(decode_string(r, policy)?)),
         b'b' =>  Ok(Property::BArray(decode_array::<R, bool>(r)?)),
         b'c' =>  Ok(Property::I8Array(decode_array::<R, i8>(r)?)),
         b'i' =>  Ok(Property::I32Array(decode_array::<R, i32>(r)?)),
         b'l' =>  Ok(Property::I64Array(decode_array::<R, i64>(r)?)),
         b'f' =>  Ok(Property::F32Array(decode_array::<R, f32>(r)?)),
         b'd' =>  Ok(Property::F64Array(decode_array::<R, f64>(r)?)),
-        _ => Err(Error::new(ErrorKind::InvalidData, "Invalid property type marker"))
+        _ => Err(FbxError::InvalidPropertyMarker { offset: offset, marker: marker })
+    }
+}
+
+/// Encode a `Property::RawArray`.
+fn encode_raw_array<W: Write>(w: &mut W, array: &[u8]) -> Result<()> {
+    w.pack(array.len() as u32)?;
+    w.write_all(array)?;
+    return Ok(());
+}
+
+/// Encode a `Property::String`.
+fn encode_string<W: Write>(w: &mut W, s: &str) -> Result<()> {
+    return encode_raw_array(w, s.as_bytes());
+}
+
+/// Encode a `Property::*Array`, always using the deflate encoding (`encoding == 1`) that
+/// [`decode_array`](fn.decode_array.html) understands. The raw encoding (`encoding == 0`)
+/// `decode_array` also accepts has no writer-side use case in this crate, so it isn't exposed
+/// here to avoid shipping an untested, unreachable path.
+fn encode_array<W: Write + Seek, T: Packed + Clone>(w: &mut W, array: &[T]) -> Result<()> {
+    let offset = w.seek(SeekFrom::Current(0))?;
+    let length = array.len() as u32;
+
+    let mut raw = Cursor::new(Vec::<u8>::with_capacity(array.len() * size_of::<T>()));
+    raw.pack_all(array)?;
+    let raw = raw.into_inner();
+
+    // `ZlibEncoder<Vec<u8>>` grows its output buffer as needed, unlike `Compress::compress_vec`
+    // which leaves it to the caller to pre-size the buffer and silently truncates the stream
+    // (returning `Status::Ok` instead of `Status::StreamEnd`) if it's too small.
+    let mut deflater = ZlibEncoder::new(Vec::<u8>::new(), Compression::default());
+    if let Err(_) = deflater.write_all(&raw) {
+        return Err(FbxError::Deflate { offset: offset });
     }
+    let compressed = match deflater.finish() {
+        Ok(compressed) => compressed,
+        Err(_) => return Err(FbxError::Deflate { offset: offset })
+    };
+
+    w.pack(length)?;
+    w.pack(1u32)?;
+    w.pack(compressed.len() as u32)?;
+    w.write_all(&compressed)?;
+    return Ok(());
 }
 
-/// Decode a [`Node`](struct.Node.html).
-pub fn decode_node<R: Read + Seek>(r: &mut R) -> Result<Option<Node>> {
-    let end_offset = r.unpack::<u32>()? as u64;
-    let property_number = r.unpack::<u32>()? as usize;
-    let properties_size = r.unpack::<u32>()? as usize;
+/// Encode a [`Property`](enum.Property.html).
+pub fn encode_property<W: Write + Seek>(w: &mut W, property: &Property) -> Result<()> {
+    match *property {
+        Property::B(b) => { w.pack(b'C')?; w.pack(if b { 1u8 } else { 0u8 })?; }
+        Property::I16(v) => { w.pack(b'Y')?; w.pack(v)?; }
+        Property::I32(v) => { w.pack(b'I')?; w.pack(v)?; }
+        Property::I64(v) => { w.pack(b'L')?; w.pack(v)?; }
+        Property::F32(v) => { w.pack(b'F')?; w.pack(v)?; }
+        Property::F64(v) => { w.pack(b'D')?; w.pack(v)?; }
+        Property::RawArray(ref v) => { w.pack(b'R')?; encode_raw_array(w, v)?; }
+        Property::String(ref v) => { w.pack(b'S')?; encode_string(w, v)?; }
+        Property::BArray(ref v) => { w.pack(b'b')?; encode_array(w, v)?; }
+        Property::I8Array(ref v) => { w.pack(b'c')?; encode_array(w, v)?; }
+        Property::I32Array(ref v) => { w.pack(b'i')?; encode_array(w, v)?; }
+        Property::I64Array(ref v) => { w.pack(b'l')?; encode_array(w, v)?; }
+        Property::F32Array(ref v) => { w.pack(b'f')?; encode_array(w, v)?; }
+        Property::F64Array(ref v) => { w.pack(b'd')?; encode_array(w, v)?; }
+    }
+    return Ok(());
+}
+
+/// Decode a node record's `end_offset`, `property_number` and name, applying `policy` to a
+/// non-UTF-8 name. Returns `None` for the NULL record that terminates a node list. Shared by
+/// [`decode_node`](fn.decode_node.html) and
+/// [`FbxReader::next_header`](struct.FbxReader.html#method.next_header) so the name-decoding
+/// fallback only has to be implemented once.
+fn decode_node_header_and_name<R: Read + Seek>(r: &mut R, layout: RecordLayout, policy: StringPolicy) -> Result<Option<(u64, usize, String)>> {
+    let (end_offset, property_number, properties_size) = decode_node_header(r, layout)?;
     let name_length = r.unpack::<u8>()? as usize;
 
     // NULL node, end of node list
@@ -121,32 +288,46 @@ pub fn decode_node<R: Read + Seek>(r: &mut R) -> Result<Option<Node>> {
         return Ok(None);
     }
 
+    let name_offset = r.seek(SeekFrom::Current(0))?;
     let mut name_buffer : Vec<u8> = repeat(0u8).take(name_length).collect();
     r.read_exact(&mut name_buffer[..])?;
-    let name = match String::from_utf8(name_buffer.clone()) {
+    let name = match String::from_utf8(name_buffer) {
         Ok(s) => s,
-        Err(_) => {
-            println!("Name Buffer: {:?}", name_buffer);
-            return Err(Error::new(ErrorKind::InvalidData, "Invalid UTF-8 characters in node name"))
+        Err(err) => match policy {
+            StringPolicy::Strict => return Err(FbxError::NonUtf8String { offset: name_offset }),
+            StringPolicy::Latin1Fallback => decode_latin1(&err.into_bytes())
         }
     };
 
+    return Ok(Some((end_offset, property_number, name)));
+}
+
+/// Decode a [`Node`](struct.Node.html). `layout` selects the field width to use for the
+/// `end_offset`, `property_number` and `properties_size` header fields; `policy` selects how to
+/// handle a non-UTF-8 node name.
+pub fn decode_node<R: Read + Seek>(r: &mut R, layout: RecordLayout, policy: StringPolicy) -> Result<Option<Node>> {
+    let (end_offset, property_number, name) = match decode_node_header_and_name(r, layout, policy)? {
+        Some(header) => header,
+        None => return Ok(None)
+    };
+
     let mut properties = Vec::<Property>::with_capacity(property_number);
     for _ in 0..property_number {
-        properties.push(decode_property(r)?);
+        properties.push(decode_property(r, policy)?);
     }
 
     return Ok(Some(Node {
         name: name,
         properties: properties,
-        subnodes: decode_node_list(r, end_offset)?
+        subnodes: decode_node_list(r, end_offset, layout, policy)?
     }));
 }
 
-/// Decode a list of [`Node`](struct.Node.html).
-pub fn decode_node_list<R: Read + Seek>(r: &mut R, end : u64) -> Result<Vec<Node>> {
+/// Decode a list of [`Node`](struct.Node.html), using `layout` for the record field width and
+/// `policy` for non-UTF-8 node names and string properties.
+pub fn decode_node_list<R: Read + Seek>(r: &mut R, end : u64, layout: RecordLayout, policy: StringPolicy) -> Result<Vec<Node>> {
     let mut nodes = Vec::<Node>::new();
-    while let Some(node) = decode_node(r)? {
+    while let Some(node) = decode_node(r, layout, policy)? {
         nodes.push(node);
         let pos = r.seek(SeekFrom::Current(0))?;
         if pos >= end {
@@ -155,3 +336,130 @@ pub fn decode_node_list<R: Read + Seek>(r: &mut R, end : u64) -> Result<Vec<Node
     }
     return Ok(nodes);
 }
+
+/// Encode the NULL record that terminates a node's list of sub-nodes.
+fn encode_null_node<W: Write>(w: &mut W, layout: RecordLayout) -> Result<()> {
+    encode_node_header(w, layout, 0, 0, 0)?;
+    w.pack(0u8)?;
+    return Ok(());
+}
+
+/// Encode a [`Node`](struct.Node.html). Because the record's `end_offset` and `properties_size`
+/// fields are only known once the node and its sub-nodes have been written, a placeholder header
+/// is written first and patched in afterwards by seeking back.
+pub fn encode_node<W: Write + Seek>(w: &mut W, node: &Node, layout: RecordLayout) -> Result<()> {
+    let header_pos = w.seek(SeekFrom::Current(0))?;
+    encode_node_header(w, layout, 0, node.properties.len(), 0)?;
+    w.pack(node.name.len() as u8)?;
+    w.write_all(node.name.as_bytes())?;
+
+    let properties_start = w.seek(SeekFrom::Current(0))?;
+    for property in &node.properties {
+        encode_property(w, property)?;
+    }
+    let properties_end = w.seek(SeekFrom::Current(0))?;
+
+    for subnode in &node.subnodes {
+        encode_node(w, subnode, layout)?;
+    }
+    encode_null_node(w, layout)?;
+
+    let end_offset = w.seek(SeekFrom::Current(0))?;
+    w.seek(SeekFrom::Start(header_pos))?;
+    encode_node_header(w, layout, end_offset, node.properties.len(), (properties_end - properties_start) as usize)?;
+    w.seek(SeekFrom::Start(end_offset))?;
+
+    return Ok(());
+}
+
+/// Encode a list of [`Node`](struct.Node.html), terminated by the NULL record that marks the end
+/// of the list.
+pub fn encode_node_list<W: Write + Seek>(w: &mut W, nodes: &[Node], layout: RecordLayout) -> Result<()> {
+    for node in nodes {
+        encode_node(w, node, layout)?;
+    }
+    return encode_null_node(w, layout);
+}
+
+/// Magic header of a `Kaydara FBX Binary` file.
+pub(crate) const FBX_MAGIC: [u8; 23] = *b"Kaydara FBX Binary  \x00\x1a\x00";
+
+/// Header and property count of a [`Node`](struct.Node.html), as returned by
+/// [`FbxReader::next_header`](struct.FbxReader.html#method.next_header) without decoding its
+/// properties or sub-nodes.
+#[derive(Clone, Debug)]
+pub struct NodeHeader {
+    /// Name of the node.
+    pub name: String,
+    /// Number of properties attached to the node.
+    pub property_number: usize,
+    end_offset: u64
+}
+
+/// A pull-based reader yielding the top-level [`Node`](struct.Node.html)s of an FBX file one at a
+/// time, instead of eagerly decoding the whole tree like [`decode_node_list`](fn.decode_node_list.html).
+pub struct FbxReader<R: Read + Seek> {
+    reader: R,
+    layout: RecordLayout,
+    policy: StringPolicy,
+    end: u64
+}
+
+impl<R: Read + Seek> FbxReader<R> {
+    /// Parse the FBX header and return a [`FbxReader`](struct.FbxReader.html) positioned at the
+    /// first top-level node. Non-UTF-8 node names and string properties are handled according to
+    /// `policy`.
+    pub fn new(mut r: R, policy: StringPolicy) -> Result<FbxReader<R>> {
+        let mut header = [0u8; 23];
+        r.read_exact(&mut header[..])?;
+        if header != FBX_MAGIC {
+            return Err(FbxError::BadMagic(header));
+        }
+        let version = r.unpack::<u32>()?;
+        let layout = RecordLayout::for_version(version);
+        return Ok(FbxReader { reader: r, layout: layout, policy: policy, end: u64::max_value() });
+    }
+
+    /// Decode and return the next top-level node, or `None` once the NULL terminator record is
+    /// reached.
+    pub fn next_node(&mut self) -> Result<Option<Node>> {
+        if self.reader.seek(SeekFrom::Current(0))? >= self.end {
+            return Ok(None);
+        }
+        return decode_node(&mut self.reader, self.layout, self.policy);
+    }
+
+    /// Decode the next top-level node's [`NodeHeader`](struct.NodeHeader.html) without decoding
+    /// its properties or sub-nodes. Pass the result to [`skip_node`](#method.skip_node) to move
+    /// the reader past the whole subtree.
+    pub fn next_header(&mut self) -> Result<Option<NodeHeader>> {
+        if self.reader.seek(SeekFrom::Current(0))? >= self.end {
+            return Ok(None);
+        }
+        let (end_offset, property_number, name) = match decode_node_header_and_name(&mut self.reader, self.layout, self.policy)? {
+            Some(header) => header,
+            None => return Ok(None)
+        };
+
+        return Ok(Some(NodeHeader { name: name, property_number: property_number, end_offset: end_offset }));
+    }
+
+    /// Seek past the subtree described by `header`, skipping its properties and sub-nodes without
+    /// decoding them.
+    pub fn skip_node(&mut self, header: &NodeHeader) -> Result<()> {
+        self.reader.seek(SeekFrom::Start(header.end_offset))?;
+        return Ok(());
+    }
+}
+
+impl<R: Read + Seek> Iterator for FbxReader<R> {
+    type Item = Result<Node>;
+
+    fn next(&mut self) -> Option<Result<Node>> {
+        match self.next_node() {
+            Ok(Some(node)) => Some(Ok(node)),
+            Ok(None) => None,
+            Err(err) => Some(Err(err))
+        }
+    }
+}